@@ -1,16 +1,117 @@
 use std::cmp::{max, min};
 use std::fs::File;
-use std::io::{prelude::*, stdin, stdout, BufReader, Stdout, Write};
+use std::io::{stdin, stdout, BufReader, Stdout, Write};
 use std::path::PathBuf;
+use ropey::Rope;
 use termion::style::{Invert, Reset};
 use termion::{cursor::Goto, event::Key, input::TermRead, raw::IntoRawMode};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
-fn to_str(s: &Vec<char>) -> String {
-    s.iter().collect()
+// Columns a tab advances to, as `KILO_TAB_STOP` does in the kilo editor.
+const TAB_STOP: usize = 4;
+
+fn to_str(s: &[String]) -> String {
+    s.concat()
+}
+
+fn to_vec(s: &str) -> Vec<String> {
+    s.graphemes(true).map(String::from).collect()
+}
+
+// Display width of a single grapheme cluster: 0 for zero-width (e.g.
+// combining marks), 2 for wide (CJK, emoji, ...), 1 otherwise.
+fn cluster_width(cluster: &str) -> usize {
+    cluster.width()
+}
+
+// Display width of `cluster` rendered starting at render column `col`.
+// Ordinary clusters have a fixed width; a tab instead stretches to the next
+// `TAB_STOP` boundary, so its width depends on where it starts.
+fn cluster_render_width(cluster: &str, col: usize) -> usize {
+    if cluster == "\t" {
+        TAB_STOP - (col % TAB_STOP)
+    } else {
+        cluster_width(cluster)
+    }
+}
+
+// Render column at which cluster `idx` starts, i.e. the logical `col` ->
+// render column mapping used for `Goto`, horizontal scrolling and the
+// status-bar column readout.
+fn display_col(line: &[String], idx: usize) -> usize {
+    let mut col = 0;
+    for cluster in &line[..idx] {
+        col += cluster_render_width(cluster, col);
+    }
+    col
+}
+
+// Cluster index whose first byte is at or after `byte_idx` in the line's
+// concatenated text, i.e. the inverse of joining `line` clusters and
+// indexing into the result with `str::find`.
+fn byte_to_col(line: &[String], byte_idx: usize) -> usize {
+    let mut acc = 0;
+    for (i, cluster) in line.iter().enumerate() {
+        if acc >= byte_idx {
+            return i;
+        }
+        acc += cluster.len();
+    }
+    line.len()
 }
 
-fn to_vec(s: &str) -> Vec<char> {
-    s.chars().collect()
+// Horizontal scroll: the leftmost cluster index still on screen once the
+// caret sits at `col`, given `text_w` columns of rendering width. Kept
+// separate from `move_caret` (which also needs the live terminal size) so
+// the scroll math itself can be exercised without a real terminal.
+fn scroll_col_offset(clusters: &[String], col: usize, col_offset: usize, text_w: usize) -> usize {
+    if col < col_offset {
+        return col;
+    }
+    let mut col_offset = col_offset;
+    while display_col(clusters, col) - display_col(clusters, col_offset) > text_w - 1 {
+        col_offset += 1;
+    }
+    col_offset
+}
+
+// Insert `c` at cluster index `idx`, merging it into the previous cluster
+// when the two combine into a single grapheme (e.g. a base letter followed
+// by a combining mark). Returns true if a new cluster was inserted.
+fn insert_grapheme(line: &mut Vec<String>, idx: usize, c: char) -> bool {
+    if idx > 0 {
+        let combined = format!("{}{}", line[idx - 1], c);
+        if combined.graphemes(true).count() == 1 {
+            line[idx - 1] = combined;
+            return false;
+        }
+    }
+    line.insert(idx, c.to_string());
+    true
+}
+
+// The character classes word-wise motion treats as boundaries: a run of
+// the same class is a "word" to skip over as one unit.
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum CharClass {
+    Space,
+    Word,
+    Punct,
+}
+
+// Columns needed to right-align every line number up to `num_lines`, e.g. 3
+// for a 999-line file, 4 once it reaches 1000.
+fn gutter_width(num_lines: usize) -> usize {
+    (max(num_lines, 1) as u32).ilog10() as usize + 1
+}
+
+fn cluster_class(cluster: &str) -> CharClass {
+    match cluster.chars().next() {
+        Some(c) if c.is_whitespace() => CharClass::Space,
+        Some(c) if c.is_alphanumeric() || c == '_' => CharClass::Word,
+        _ => CharClass::Punct,
+    }
 }
 
 enum State {
@@ -21,6 +122,16 @@ enum State {
 enum Command {
     Open,
     Save,
+    Find,
+}
+
+// A discard confirmation in progress, armed by a first `Ctrl+Q`/`Ctrl+O` on
+// a modified buffer: the matching key must be pressed again immediately to
+// proceed, otherwise any other keystroke disarms it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DiscardAction {
+    Quit,
+    Open,
 }
 
 trait Buffer {
@@ -28,12 +139,130 @@ trait Buffer {
     fn backspace(&mut self);
     fn delete(&mut self);
     fn move_caret(&mut self, row: i32, col: i32);
+    // Move to the start of the next/previous word, a run of clusters of the
+    // same `CharClass`; whitespace between runs is skipped over.
+    fn move_word_right(&mut self);
+    fn move_word_left(&mut self);
+}
+
+// A reversible FileBuffer edit, recorded for undo/redo. Insert/Delete carry
+// the clusters affected so consecutive single-cluster edits at adjacent
+// positions can be coalesced into one entry (so undo removes a whole typed
+// word, not just its last letter).
+#[derive(Clone)]
+enum EditOp {
+    Insert { row: usize, col: usize, text: Vec<String> },
+    Delete { row: usize, col: usize, text: Vec<String> },
+    // A typed character merged into the cluster at `col` (e.g. a combining
+    // mark) instead of starting a new one, turning `old` into `new` in
+    // place. Cluster count is unchanged, so `col` stays valid in both
+    // directions, unlike `Insert`/`Delete`.
+    Replace { row: usize, col: usize, old: String, new: String },
+    SplitLine { row: usize, col: usize },
+    JoinLine { row: usize, col: usize },
+}
+
+impl EditOp {
+    fn inverted(&self) -> EditOp {
+        match self {
+            EditOp::Insert { row, col, text } => EditOp::Delete {
+                row: *row,
+                col: *col,
+                text: text.clone(),
+            },
+            EditOp::Delete { row, col, text } => EditOp::Insert {
+                row: *row,
+                col: *col,
+                text: text.clone(),
+            },
+            EditOp::Replace { row, col, old, new } => EditOp::Replace {
+                row: *row,
+                col: *col,
+                old: new.clone(),
+                new: old.clone(),
+            },
+            EditOp::SplitLine { row, col } => EditOp::JoinLine {
+                row: *row,
+                col: *col,
+            },
+            EditOp::JoinLine { row, col } => EditOp::SplitLine {
+                row: *row,
+                col: *col,
+            },
+        }
+    }
+
+    // Try to merge `next`, a freshly recorded edit, into `self`. Returns
+    // true if it was merged.
+    fn coalesce(&mut self, next: &EditOp) -> bool {
+        match (self, next) {
+            (
+                EditOp::Insert { row, col, text },
+                EditOp::Insert {
+                    row: r2,
+                    col: c2,
+                    text: t2,
+                },
+            ) if *row == *r2 && *c2 == *col + text.len() => {
+                text.extend(t2.iter().cloned());
+                true
+            }
+            (
+                EditOp::Delete { row, col, text },
+                EditOp::Delete {
+                    row: r2,
+                    col: c2,
+                    text: t2,
+                },
+            ) if *row == *r2 && *c2 == *col => {
+                // Forward `Delete` repeated: each press removes the next
+                // cluster at the same position.
+                text.extend(t2.iter().cloned());
+                true
+            }
+            (
+                EditOp::Delete { row, col, text },
+                EditOp::Delete {
+                    row: r2,
+                    col: c2,
+                    text: t2,
+                },
+            ) if *row == *r2 && *c2 + 1 == *col => {
+                // Repeated `Backspace`: each press removes the cluster just
+                // before the caret, so the run grows to the left.
+                *col = *c2;
+                let mut merged = t2.clone();
+                merged.extend(text.iter().cloned());
+                *text = merged;
+                true
+            }
+            (
+                EditOp::Replace { row, col, new, .. },
+                EditOp::Replace {
+                    row: r2,
+                    col: c2,
+                    old: o2,
+                    new: n2,
+                },
+            ) if *row == *r2 && *col == *c2 && *new == *o2 => {
+                // Another combining mark extending the same cluster.
+                *new = n2.clone();
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 struct Editor {
     file_buffer: FileBuffer,
     state: State,
     message: Option<String>,
+    undo_stack: Vec<EditOp>,
+    redo_stack: Vec<EditOp>,
+    last_query: Option<String>,
+    search_origin: (usize, usize),
+    pending_discard: Option<DiscardAction>,
 }
 
 impl Editor {
@@ -42,9 +271,18 @@ impl Editor {
             file_buffer: FileBuffer::new(),
             state: State::Femto,
             message: None,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            last_query: None,
+            search_origin: (0, 0),
+            pending_discard: None,
         }
     }
 
+    fn toggle_gutter(&mut self) {
+        self.file_buffer.show_gutter = !self.file_buffer.show_gutter;
+    }
+
     fn buffer(&mut self) -> &mut dyn Buffer {
         match &mut self.state {
             State::Femto => &mut self.file_buffer,
@@ -55,34 +293,155 @@ impl Editor {
     fn push(&mut self, c: char) {
         if c == '\n' {
             match &self.state {
-                State::Femto => self.buffer().push(c),
+                State::Femto => {
+                    let (row, col) = (self.file_buffer.row, self.file_buffer.col);
+                    self.record(EditOp::SplitLine { row, col });
+                    self.buffer().push(c);
+                }
                 State::Cmd((state, buffer)) => {
                     let line = buffer.line.clone();
                     match state {
                         Command::Open => self.open(PathBuf::from(&to_str(&line))),
                         Command::Save => self.save(PathBuf::from(&to_str(&line))),
+                        Command::Find => self.confirm_find(to_str(&line)),
                     }
                 }
             }
         } else {
+            if let State::Femto = self.state {
+                let (row, col) = (self.file_buffer.row, self.file_buffer.col);
+                if self.file_buffer.would_merge(row, col, c) {
+                    let old = self.file_buffer.line_clusters(row)[col - 1].clone();
+                    let new = format!("{}{}", old, c);
+                    self.record(EditOp::Replace {
+                        row,
+                        col: col - 1,
+                        old,
+                        new,
+                    });
+                } else {
+                    self.record(EditOp::Insert {
+                        row,
+                        col,
+                        text: vec![c.to_string()],
+                    });
+                }
+            }
             self.buffer().push(c);
+            self.update_search();
+        }
+    }
+
+    fn backspace(&mut self) {
+        if let State::Femto = self.state {
+            let (row, col) = (self.file_buffer.row, self.file_buffer.col);
+            if col == 0 && row != 0 {
+                let prev_len = self.file_buffer.line_clusters(row - 1).len();
+                self.record(EditOp::JoinLine {
+                    row: row - 1,
+                    col: prev_len,
+                });
+            } else if col != 0 {
+                let cluster = self.file_buffer.line_clusters(row)[col - 1].clone();
+                self.record(EditOp::Delete {
+                    row,
+                    col: col - 1,
+                    text: vec![cluster],
+                });
+            }
+        }
+        self.buffer().backspace();
+        self.update_search();
+    }
+
+    fn delete(&mut self) {
+        if let State::Femto = self.state {
+            let (row, col) = (self.file_buffer.row, self.file_buffer.col);
+            let line_len = self.file_buffer.line_clusters(row).len();
+            if col == line_len && row != self.file_buffer.num_lines() - 1 {
+                self.record(EditOp::JoinLine { row, col });
+            } else if col != line_len {
+                let cluster = self.file_buffer.line_clusters(row)[col].clone();
+                self.record(EditOp::Delete {
+                    row,
+                    col,
+                    text: vec![cluster],
+                });
+            }
+        }
+        self.buffer().delete();
+        self.update_search();
+    }
+
+    // Push a new (possibly coalesced) edit onto the undo stack. Any fresh
+    // edit invalidates the redo history.
+    fn record(&mut self, op: EditOp) {
+        self.redo_stack.clear();
+        if let Some(last) = self.undo_stack.last_mut() {
+            if last.coalesce(&op) {
+                return;
+            }
+        }
+        self.undo_stack.push(op);
+    }
+
+    fn undo(&mut self) {
+        if let Some(op) = self.undo_stack.pop() {
+            let caret = self.file_buffer.apply_op(&op.inverted());
+            self.file_buffer.row = caret.0;
+            self.file_buffer.col = caret.1;
+            self.redo_stack.push(op);
         }
     }
 
+    fn redo(&mut self) {
+        if let Some(op) = self.redo_stack.pop() {
+            let caret = self.file_buffer.apply_op(&op);
+            self.file_buffer.row = caret.0;
+            self.file_buffer.col = caret.1;
+            self.undo_stack.push(op);
+        }
+    }
+
+    // Arm/check a pending discard confirmation for `action`. Returns true if
+    // it's safe to proceed (buffer is clean, or this is the confirming
+    // repeat press), false if a warning was shown and the caller should
+    // wait for the user to press the key again.
+    fn confirm_discard(&mut self, action: DiscardAction) -> bool {
+        if !self.file_buffer.modified {
+            return true;
+        }
+        if self.pending_discard == Some(action) {
+            self.pending_discard = None;
+            return true;
+        }
+        self.pending_discard = Some(action);
+        self.show_message("Unsaved changes — press again to discard".to_string());
+        false
+    }
+
     fn start_open(&mut self) {
         self.state = State::Cmd((Command::Open, LineBuffer::default()));
     }
 
     fn open(&mut self, path: PathBuf) {
         match self.file_buffer.load(path.clone()) {
-            Ok(_) => self.exit_command(),
+            Ok(_) => {
+                // The new buffer shares none of the old one's history or
+                // in-flight state.
+                self.undo_stack.clear();
+                self.redo_stack.clear();
+                self.pending_discard = None;
+                self.search_origin = (0, 0);
+                self.exit_command();
+            }
             Err(err) => self.show_message(err.to_string()),
         }
     }
 
     fn start_save(&mut self) {
         let mut buffer = LineBuffer::default();
-        buffer.line = self.file_buffer.path.to_str().unwrap().chars().collect();
+        buffer.line = to_vec(self.file_buffer.path.to_str().unwrap());
         buffer.col = buffer.line.len();
         self.state = State::Cmd((Command::Save, buffer));
     }
@@ -94,15 +453,137 @@ impl Editor {
         }
     }
 
+    fn start_find(&mut self) {
+        self.search_origin = (self.file_buffer.row, self.file_buffer.col);
+        let mut buffer = LineBuffer::default();
+        if let Some(query) = &self.last_query {
+            buffer.line = to_vec(query);
+            buffer.col = buffer.line.len();
+        }
+        self.state = State::Cmd((Command::Find, buffer));
+        self.update_search();
+    }
+
+    fn confirm_find(&mut self, query: String) {
+        if !query.is_empty() {
+            self.last_query = Some(query);
+        }
+        self.exit_command();
+    }
+
+    fn cancel_find(&mut self) {
+        let (row, col) = self.search_origin;
+        self.file_buffer.row = row;
+        self.file_buffer.col = col;
+        self.file_buffer.move_caret(0, 0);
+        self.exit_command();
+    }
+
+    // Re-run the search from `search_origin` with the prompt's current
+    // query and move the caret to the first match, as the user types.
+    fn update_search(&mut self) {
+        let query = match &self.state {
+            State::Cmd((Command::Find, buffer)) => to_str(&buffer.line),
+            _ => return,
+        };
+        if query.is_empty() {
+            return;
+        }
+        if let Some((row, col)) = self.find(&query, self.search_origin, true) {
+            self.file_buffer.row = row;
+            self.file_buffer.col = col;
+            self.file_buffer.move_caret(0, 0);
+        }
+    }
+
+    fn find_next(&mut self) {
+        let query = match &self.state {
+            State::Cmd((Command::Find, buffer)) => to_str(&buffer.line),
+            _ => return,
+        };
+        if query.is_empty() {
+            return;
+        }
+        let from = (self.file_buffer.row, self.file_buffer.col + 1);
+        if let Some((row, col)) = self.find(&query, from, true) {
+            self.file_buffer.row = row;
+            self.file_buffer.col = col;
+            self.file_buffer.move_caret(0, 0);
+        }
+    }
+
+    fn find_prev(&mut self) {
+        let query = match &self.state {
+            State::Cmd((Command::Find, buffer)) => to_str(&buffer.line),
+            _ => return,
+        };
+        if query.is_empty() {
+            return;
+        }
+        let from = (self.file_buffer.row, self.file_buffer.col);
+        if let Some((row, col)) = self.find(&query, from, false) {
+            self.file_buffer.row = row;
+            self.file_buffer.col = col;
+            self.file_buffer.move_caret(0, 0);
+        }
+    }
+
+    // Search for `query` starting at (and wrapping around) `from`. Forward
+    // search includes matches starting at `from` itself; backward search
+    // only considers matches strictly before it, so repeated calls advance.
+    // `from`'s column is clamped to each row's length, since callers (e.g.
+    // `find_next` stepping one past a caret already at end-of-line) may pass
+    // one that's run off the end of the starting row.
+    fn find(&self, query: &str, from: (usize, usize), forward: bool) -> Option<(usize, usize)> {
+        let total = self.file_buffer.num_lines();
+        if forward {
+            for offset in 0..=total {
+                let row = (from.0 + offset) % total;
+                let clusters = self.file_buffer.line_clusters(row);
+                let text = to_str(&clusters);
+                let from_col = if offset == 0 {
+                    min(from.1, clusters.len())
+                } else {
+                    0
+                };
+                let start_byte: usize = clusters[..from_col].iter().map(|c| c.len()).sum();
+                if let Some(rel) = text[start_byte..].find(query) {
+                    return Some((row, byte_to_col(&clusters, start_byte + rel)));
+                }
+            }
+        } else {
+            for offset in 0..=total {
+                let row = (from.0 + total - offset) % total;
+                let clusters = self.file_buffer.line_clusters(row);
+                let text = to_str(&clusters);
+                let end_col = if offset == 0 {
+                    min(from.1, clusters.len())
+                } else {
+                    clusters.len()
+                };
+                let end_byte: usize = clusters[..end_col].iter().map(|c| c.len()).sum();
+                if let Some(rel) = text[..end_byte].rfind(query) {
+                    return Some((row, byte_to_col(&clusters, rel)));
+                }
+            }
+        }
+        None
+    }
+
     fn prompt(&self) -> (&str, String, usize) {
         match &self.state {
             State::Femto => match &self.message {
                 Some(message) => ("", message.clone(), 0),
-                None => ("femto", String::new(), 0),
+                None => {
+                    let marker = if self.file_buffer.modified { "*" } else { "" };
+                    let name = self.file_buffer.path.to_str().unwrap_or("");
+                    ("femto: ", format!("{}{}", marker, name), 0)
+                }
             },
             State::Cmd((state, buf)) => match state {
                 Command::Open => ("Open file at: ", to_str(&buf.line), buf.col),
                 Command::Save => ("Save file at: ", to_str(&buf.line), buf.col),
+                Command::Find => ("Find: ", to_str(&buf.line), buf.col),
             },
         }
     }
@@ -117,13 +598,20 @@ impl Editor {
     }
 }
 
+// Text storage for the edited file. `row`/`col` (a grapheme-cluster index)
+// remain the public caret model; internally they are translated to a flat
+// rope char offset, so the rope insert/delete itself is O(log n) at any
+// point in a large file. Translating `col` to that offset still segments
+// the caret's line into clusters, which costs O(line length).
 struct FileBuffer {
     row_offset: usize,
     col_offset: usize,
     row: usize,
     col: usize,
     path: PathBuf,
-    lines: Vec<Vec<char>>,
+    rope: Rope,
+    modified: bool,
+    show_gutter: bool,
 }
 
 impl FileBuffer {
@@ -134,78 +622,214 @@ impl FileBuffer {
             row: 0,
             col: 0,
             path: PathBuf::default(),
-            lines: vec![vec![]],
+            rope: Rope::new(),
+            modified: false,
+            show_gutter: false,
+        }
+    }
+
+    // Ropey counts a phantom trailing line after a final '\n'; our model
+    // counts only the lines that actually hold content.
+    fn num_lines(&self) -> usize {
+        let lines = self.rope.len_lines();
+        if self.rope.len_chars() > 0 && self.rope.char(self.rope.len_chars() - 1) == '\n' {
+            lines - 1
+        } else {
+            lines
+        }
+    }
+
+    // Columns the line-number gutter takes up: 0 when toggled off, otherwise
+    // the digits plus a 1-column separator, as rendered by `print_screen`.
+    fn gutter(&self) -> usize {
+        if self.show_gutter {
+            gutter_width(self.num_lines()) + 1
+        } else {
+            0
+        }
+    }
+
+    // Grapheme clusters making up visual line `row`, with its line
+    // terminator stripped.
+    fn line_clusters(&self, row: usize) -> Vec<String> {
+        let mut text = self.rope.line(row).to_string();
+        if text.ends_with('\n') {
+            text.pop();
+            if text.ends_with('\r') {
+                text.pop();
+            }
         }
+        to_vec(&text)
     }
 
-    fn line(&mut self) -> &mut Vec<char> {
-        self.lines.get_mut(self.row).unwrap()
+    // Rope char offset of cluster `col` on line `row`.
+    fn char_offset(&self, row: usize, col: usize) -> usize {
+        let clusters = self.line_clusters(row);
+        let within: usize = clusters[..col].iter().map(|c| c.chars().count()).sum();
+        self.rope.line_to_char(row) + within
+    }
+
+    // Whether typing `c` right before `(row, col)` would extend the
+    // preceding grapheme cluster (e.g. a combining mark) rather than start
+    // a new one.
+    fn would_merge(&self, row: usize, col: usize, c: char) -> bool {
+        if col == 0 {
+            return false;
+        }
+        let clusters = self.line_clusters(row);
+        let combined = format!("{}{}", clusters[col - 1], c);
+        combined.graphemes(true).count() == 1
+    }
+
+    // Class of the cluster at `(row, col)`, or `Space` past the end of the
+    // line — word motion treats each line break as a whitespace boundary.
+    fn class_at(&self, row: usize, col: usize) -> CharClass {
+        let clusters = self.line_clusters(row);
+        match clusters.get(col) {
+            Some(cluster) => cluster_class(cluster),
+            None => CharClass::Space,
+        }
+    }
+
+    // One cluster to the right of `(row, col)`, crossing onto the next line
+    // at end-of-line. `None` at the end of the buffer.
+    fn step_right(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        if col < self.line_clusters(row).len() {
+            Some((row, col + 1))
+        } else if row + 1 < self.num_lines() {
+            Some((row + 1, 0))
+        } else {
+            None
+        }
+    }
+
+    // One cluster to the left of `(row, col)`, crossing onto the previous
+    // line at its end. `None` at the start of the buffer.
+    fn step_left(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        if col > 0 {
+            Some((row, col - 1))
+        } else if row > 0 {
+            Some((row - 1, self.line_clusters(row - 1).len()))
+        } else {
+            None
+        }
     }
 
     fn load(&mut self, path: PathBuf) -> Result<(), std::io::Error> {
         let file = File::open(path.clone())?;
-        let converter = |l: Result<String, _>| to_vec(&l.unwrap());
-        self.lines = BufReader::new(file).lines().map(converter).collect();
+        self.rope = Rope::from_reader(BufReader::new(file))?;
         self.path = path;
         self.row = 0;
         self.col = 0;
+        self.modified = false;
         Ok(())
     }
 
-    fn save(&self, path: PathBuf) -> Result<(), std::io::Error> {
-        let mut file = File::create(path.clone())?;
-        for line in self.lines.iter() {
-            writeln!(file, "{}", to_str(line)).unwrap();
-        }
+    fn save(&mut self, path: PathBuf) -> Result<(), std::io::Error> {
+        let file = File::create(path.clone())?;
+        self.rope.write_to(file)?;
+        self.modified = false;
         Ok(())
     }
+
+    // Apply an `EditOp` forward, i.e. exactly as it happened the first time
+    // it was recorded. Undo instead applies `op.inverted()`. Returns the
+    // caret position the edit leaves behind.
+    fn apply_op(&mut self, op: &EditOp) -> (usize, usize) {
+        self.modified = true;
+        match op {
+            EditOp::Insert { row, col, text } => {
+                let offset = self.char_offset(*row, *col);
+                self.rope.insert(offset, &to_str(text));
+                (*row, *col + text.len())
+            }
+            EditOp::Delete { row, col, text } => {
+                let start = self.char_offset(*row, *col);
+                let chars: usize = text.iter().map(|c| c.chars().count()).sum();
+                self.rope.remove(start..start + chars);
+                (*row, *col)
+            }
+            EditOp::Replace { row, col, old, new } => {
+                let start = self.char_offset(*row, *col);
+                let old_len = old.chars().count();
+                self.rope.remove(start..start + old_len);
+                self.rope.insert(start, new);
+                (*row, *col + 1)
+            }
+            EditOp::SplitLine { row, col } => {
+                let offset = self.char_offset(*row, *col);
+                self.rope.insert_char(offset, '\n');
+                (*row + 1, 0)
+            }
+            EditOp::JoinLine { row, col } => {
+                let newline_at = self.rope.line_to_char(*row + 1) - 1;
+                self.rope.remove(newline_at..newline_at + 1);
+                (*row, *col)
+            }
+        }
+    }
 }
 
 impl Buffer for FileBuffer {
     fn push(&mut self, c: char) {
-        let (col, row) = (self.col, self.row);
+        let (row, col) = (self.row, self.col);
+        let offset = self.char_offset(row, col);
+        self.modified = true;
 
         if c == '\n' {
-            let new_line = self.line().drain(col..).collect();
-            self.lines.insert(row + 1, new_line);
+            self.rope.insert_char(offset, '\n');
             self.move_caret(1, -(col as i32));
             return;
         }
 
-        self.line().insert(col, c);
-        self.move_caret(0, 1);
+        // A combining mark extends the preceding cluster rather than
+        // starting a new one; either way it's inserted right after it.
+        let merges = self.would_merge(row, col, c);
+        self.rope.insert_char(offset, c);
+        if !merges {
+            self.move_caret(0, 1);
+        }
     }
 
     fn backspace(&mut self) {
-        let (col, row) = (self.col, self.row);
+        let (row, col) = (self.row, self.col);
 
         if col == 0 && row != 0 {
-            let line = self.lines.remove(row);
+            let prev_len = self.line_clusters(row - 1).len();
+            let newline_at = self.rope.line_to_char(row) - 1;
+            self.rope.remove(newline_at..newline_at + 1);
+            self.modified = true;
             self.move_caret(-1, 0);
-            let len = self.line().len() as i32 - col as i32;
-            self.move_caret(0, len);
-            self.line().extend(line.iter());
+            self.move_caret(0, prev_len as i32 - col as i32);
         } else if col != 0 {
-            self.line().remove(col - 1);
+            let start = self.char_offset(row, col - 1);
+            let end = self.char_offset(row, col);
+            self.rope.remove(start..end);
+            self.modified = true;
             self.move_caret(0, -1);
         }
     }
 
     fn delete(&mut self) {
-        let (col, row) = (self.col, self.row);
+        let (row, col) = (self.row, self.col);
+        let clusters = self.line_clusters(row);
 
-        if col == self.line().len() && row != self.lines.len() - 1 {
-            let line = self.lines.remove(row + 1);
-            self.line().extend(line.iter());
-        } else if col != self.line().len() {
-            self.line().remove(col);
+        if col == clusters.len() && row != self.num_lines() - 1 {
+            let newline_at = self.rope.line_to_char(row + 1) - 1;
+            self.rope.remove(newline_at..newline_at + 1);
+            self.modified = true;
+        } else if col != clusters.len() {
+            let start = self.char_offset(row, col);
+            let end = self.char_offset(row, col + 1);
+            self.rope.remove(start..end);
+            self.modified = true;
         }
     }
 
     fn move_caret(&mut self, row: i32, col: i32) {
         let (w, h) = termion::terminal_size().expect("Unsupported terminal.");
 
-        let num_lines = self.lines.len() as i32;
+        let num_lines = self.num_lines() as i32;
         self.row = min(max(self.row as i32 + row, 0), num_lines - 1) as usize;
         if self.row < self.row_offset {
             self.row_offset = self.row;
@@ -213,26 +837,83 @@ impl Buffer for FileBuffer {
             self.row_offset = self.row - (h as usize - 2);
         }
 
-        let line_len = self.line().len() as i32;
+        let clusters = self.line_clusters(self.row);
+        let line_len = clusters.len() as i32;
         self.col = min(max(self.col as i32 + col, 0), line_len) as usize;
-        if self.col < self.col_offset {
-            self.col_offset = self.col;
-        } else if self.col > self.col_offset + (w as usize - 1) {
-            self.col_offset = self.col - (w as usize - 1);
+
+        // Columns actually available for text, matching what `print_screen`
+        // renders into once the gutter (if toggled on) eats into the width.
+        let text_w = w as usize - self.gutter();
+
+        // col_offset indexes clusters (not display columns) so that it only
+        // ever advances by whole grapheme clusters: a wide character can
+        // therefore never be split across the left/right screen edge.
+        self.col_offset = scroll_col_offset(&clusters, self.col, self.col_offset, text_w);
+    }
+
+    fn move_word_right(&mut self) {
+        let (mut row, mut col) = (self.row, self.col);
+
+        // Skip the rest of the run the caret is in, if any.
+        let start_class = self.class_at(row, col);
+        if start_class != CharClass::Space {
+            while self.class_at(row, col) == start_class {
+                match self.step_right(row, col) {
+                    Some(next) => (row, col) = next,
+                    None => break,
+                }
+            }
         }
+        // Skip whitespace (crossing line breaks) to land on the next word.
+        while self.class_at(row, col) == CharClass::Space {
+            match self.step_right(row, col) {
+                Some(next) => (row, col) = next,
+                None => break,
+            }
+        }
+
+        self.row = row;
+        self.col = col;
+        self.move_caret(0, 0);
+    }
+
+    fn move_word_left(&mut self) {
+        let (mut row, mut col) = (self.row, self.col);
+
+        // Skip whitespace to the left (crossing line breaks).
+        while let Some((r, c)) = self.step_left(row, col) {
+            if self.class_at(r, c) != CharClass::Space {
+                break;
+            }
+            (row, col) = (r, c);
+        }
+        // Skip the run to the left we're now adjacent to, landing on its start.
+        if let Some((r, c)) = self.step_left(row, col) {
+            let class = self.class_at(r, c);
+            (row, col) = (r, c);
+            while let Some((r2, c2)) = self.step_left(row, col) {
+                if self.class_at(r2, c2) != class {
+                    break;
+                }
+                (row, col) = (r2, c2);
+            }
+        }
+
+        self.row = row;
+        self.col = col;
+        self.move_caret(0, 0);
     }
 }
 
 #[derive(Default)]
 struct LineBuffer {
     col: usize,
-    line: Vec<char>,
+    line: Vec<String>,
 }
 
 impl Buffer for LineBuffer {
     fn push(&mut self, c: char) {
-        if c != '\n' {
-            self.line.insert(self.col, c);
+        if c != '\n' && insert_grapheme(&mut self.line, self.col, c) {
             self.move_caret(0, 1);
         }
     }
@@ -254,6 +935,35 @@ impl Buffer for LineBuffer {
         let line_len = self.line.len() as i32;
         self.col = min(max(self.col as i32 + col, 0), line_len) as usize;
     }
+
+    fn move_word_right(&mut self) {
+        let len = self.line.len();
+        let start_class = if self.col < len {
+            cluster_class(&self.line[self.col])
+        } else {
+            CharClass::Space
+        };
+        if start_class != CharClass::Space {
+            while self.col < len && cluster_class(&self.line[self.col]) == start_class {
+                self.col += 1;
+            }
+        }
+        while self.col < len && cluster_class(&self.line[self.col]) == CharClass::Space {
+            self.col += 1;
+        }
+    }
+
+    fn move_word_left(&mut self) {
+        while self.col > 0 && cluster_class(&self.line[self.col - 1]) == CharClass::Space {
+            self.col -= 1;
+        }
+        if self.col > 0 {
+            let class = cluster_class(&self.line[self.col - 1]);
+            while self.col > 0 && cluster_class(&self.line[self.col - 1]) == class {
+                self.col -= 1;
+            }
+        }
+    }
 }
 
 fn main() {
@@ -281,24 +991,67 @@ fn main() {
 fn print_screen(stdout: &mut Stdout, editor: &mut Editor) {
     let file_buf = &editor.file_buffer;
     let (roff, coff) = (file_buf.row_offset, file_buf.col_offset);
-    let (r, c) = (file_buf.row + 1, file_buf.col + 1);
+    let caret_clusters = file_buf.line_clusters(file_buf.row);
+    let (r, c) = (
+        file_buf.row + 1,
+        display_col(&caret_clusters, file_buf.col) + 1,
+    );
     let (w, h) = termion::terminal_size().expect("Unsupported terminal.");
 
+    // Gutter width is sized to the line count of the whole file, not just
+    // the rows on screen, so it doesn't jiggle as you scroll. 0 when the
+    // gutter is toggled off, otherwise the digits plus a 1-column separator.
+    let num_width = gutter_width(file_buf.num_lines());
+    let gutter = file_buf.gutter();
+    let text_w = w as usize - gutter;
+
     // Clear and start writing from origin
     write!(stdout, "{}{}", termion::clear::All, Goto(1, 1)).unwrap();
 
     for i in roff..(roff + h as usize - 1) {
-        if i < file_buf.lines.len() {
+        if file_buf.show_gutter && i < file_buf.num_lines() {
+            let number = format!("{:>width$} ", i + 1, width = num_width);
+            if i == file_buf.row {
+                write!(stdout, "{}{}{}", Invert, number, Reset).unwrap();
+            } else {
+                write!(stdout, "{}", number).unwrap();
+            }
+        } else if file_buf.show_gutter {
+            // Blank gutter for filler rows past end-of-file, so the `~`
+            // doesn't get a fake, incrementing line number next to it.
+            write!(stdout, "{}", " ".repeat(gutter)).unwrap();
+        }
+
+        if i < file_buf.num_lines() {
             // Content
-            let line = file_buf.lines.get(i).unwrap();
+            let line = file_buf.line_clusters(i);
 
             if line.len() < coff {
                 write!(stdout, "\n\r").unwrap();
                 continue;
             }
 
-            let part = &line[coff..min(coff + w as usize, line.len())];
-            write!(stdout, "{}\n\r", to_str(&Vec::from(part))).unwrap();
+            // Take whole clusters, starting at the coff-th one, until the
+            // accumulated display width would overflow the screen width so
+            // a wide character is never cut in half at the right edge. Tabs
+            // are expanded to spaces up to the next tab stop.
+            let mut rendered = String::new();
+            let mut render_col = display_col(&line, coff);
+            let mut used = 0;
+            for cluster in &line[coff..] {
+                let cw = cluster_render_width(cluster, render_col);
+                if used + cw > text_w {
+                    break;
+                }
+                if cluster == "\t" {
+                    rendered.push_str(&" ".repeat(cw));
+                } else {
+                    rendered.push_str(cluster);
+                }
+                render_col += cw;
+                used += cw;
+            }
+            write!(stdout, "{}\n\r", rendered).unwrap();
         } else {
             // ~ as filler for parts of the window that are outside the buffer
             write!(stdout, "~\n\r").unwrap();
@@ -325,7 +1078,16 @@ fn print_screen(stdout: &mut Stdout, editor: &mut Editor) {
 
     // Draw cursor on the right place
     match editor.state {
-        State::Femto => write!(stdout, "{}", Goto((c - coff) as u16, (r - roff) as u16)).unwrap(),
+        State::Femto => {
+            let cursor_col =
+                display_col(&caret_clusters, file_buf.col) - display_col(&caret_clusters, coff);
+            write!(
+                stdout,
+                "{}",
+                Goto((cursor_col + gutter + 1) as u16, (r - roff) as u16)
+            )
+            .unwrap()
+        }
         _ => write!(stdout, "{}", Goto(cmd_cur_pos - start as u16, h)).unwrap(),
     }
     // Ensure everything visible
@@ -335,22 +1097,273 @@ fn print_screen(stdout: &mut Stdout, editor: &mut Editor) {
 }
 
 fn handle_keys(editor: &mut Editor) -> bool {
-    let c = stdin().keys().next().unwrap();
-    match c.unwrap() {
+    let finding = matches!(editor.state, State::Cmd((Command::Find, _)));
+    let key = stdin().keys().next().unwrap().unwrap();
+
+    let repeats_pending = matches!(
+        (editor.pending_discard, &key),
+        (Some(DiscardAction::Quit), Key::Ctrl('q')) | (Some(DiscardAction::Open), Key::Ctrl('o'))
+    );
+    if !repeats_pending {
+        editor.pending_discard = None;
+    }
+
+    match key {
         Key::Char(c) => editor.push(c),
-        Key::Ctrl('q') => return true,
-        Key::Ctrl('o') => editor.start_open(),
+        Key::Ctrl('q') if editor.confirm_discard(DiscardAction::Quit) => return true,
+        Key::Ctrl('o') if editor.confirm_discard(DiscardAction::Open) => editor.start_open(),
         Key::Ctrl('s') => editor.start_save(),
-        Key::Backspace => editor.buffer().backspace(),
-        Key::Delete => editor.buffer().delete(),
+        Key::Ctrl('f') => editor.start_find(),
+        Key::Ctrl('z') => editor.undo(),
+        Key::Ctrl('y') => editor.redo(),
+        Key::Ctrl('g') => editor.toggle_gutter(),
+        Key::Backspace => editor.backspace(),
+        Key::Delete => editor.delete(),
+        Key::Esc if finding => editor.cancel_find(),
         Key::Esc => editor.exit_command(),
+        Key::Up if finding => editor.find_prev(),
+        Key::Down if finding => editor.find_next(),
+        // termion has no way to decode a modified arrow key, so word motion
+        // is bound to the Emacs/readline convention (Alt+B / Alt+F) instead.
+        Key::Alt('b') => editor.buffer().move_word_left(),
+        Key::Alt('f') => editor.buffer().move_word_right(),
         Key::Left => editor.buffer().move_caret(0, -1),
         Key::Right => editor.buffer().move_caret(0, 1),
         Key::Up => editor.buffer().move_caret(-1, 0),
         Key::Down => editor.buffer().move_caret(1, 0),
-        Key::Home => editor.buffer().move_caret(0, std::i32::MIN / 2),
-        Key::End => editor.buffer().move_caret(0, std::i32::MAX / 2),
+        Key::Home => editor.buffer().move_caret(0, i32::MIN / 2),
+        Key::End => editor.buffer().move_caret(0, i32::MAX / 2),
         _ => {}
     }
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn buffer_with(text: &str) -> FileBuffer {
+        let mut buffer = FileBuffer::new();
+        buffer.rope = Rope::from_str(text);
+        buffer
+    }
+
+    fn editor_with(text: &str) -> Editor {
+        let mut editor = Editor::new();
+        editor.file_buffer = buffer_with(text);
+        editor
+    }
+
+    #[test]
+    fn find_from_past_end_of_line_does_not_panic() {
+        // "hello" is a 5-cluster line; starting a forward search one past
+        // it (as `find_next` does when the caret already sits at
+        // end-of-line) must clamp instead of indexing off the end.
+        let editor = editor_with("hello\nworld\n");
+        assert_eq!(editor.find("wor", (0, 6), true), Some((1, 0)));
+    }
+
+    #[test]
+    fn find_wraps_around_to_the_start() {
+        let editor = editor_with("foo bar\nbaz foo\n");
+        assert_eq!(editor.find("foo", (1, 5), true), Some((0, 0)));
+    }
+
+    #[test]
+    fn scroll_col_offset_holds_still_until_the_caret_runs_off_screen() {
+        let clusters = to_vec(&"a".repeat(20));
+        // Caret well within the window: no need to scroll yet.
+        assert_eq!(scroll_col_offset(&clusters, 5, 0, 10), 0);
+        // Caret one past the last visible column: scroll by exactly one.
+        assert_eq!(scroll_col_offset(&clusters, 10, 0, 10), 1);
+        // Caret moved back left of the current offset: snap straight to it.
+        assert_eq!(scroll_col_offset(&clusters, 2, 8, 10), 2);
+    }
+
+    #[test]
+    fn scroll_col_offset_accounts_for_a_narrower_text_width() {
+        // Simulates the gutter eating into the rendered width: with the
+        // same caret position, a smaller `text_w` must scroll sooner.
+        let clusters = to_vec(&"a".repeat(20));
+        assert_eq!(scroll_col_offset(&clusters, 9, 0, 10), 0);
+        assert_eq!(scroll_col_offset(&clusters, 9, 0, 8), 2);
+    }
+
+    #[test]
+    fn display_col_expands_tabs_to_the_next_stop() {
+        // TAB_STOP is 4: a tab at column 0 advances to column 4, while the
+        // second tab, starting at column 5 after the "a", advances only 3
+        // to reach the next stop at column 8.
+        let line = to_vec("\ta\tb");
+        assert_eq!(display_col(&line, 1), 4);
+        assert_eq!(display_col(&line, 2), 5);
+        assert_eq!(display_col(&line, 3), 8);
+        assert_eq!(display_col(&line, 4), 9);
+    }
+
+    #[test]
+    fn coalesce_merges_consecutive_forward_inserts() {
+        let mut op = EditOp::Insert {
+            row: 0,
+            col: 0,
+            text: vec!["a".to_string()],
+        };
+        let next = EditOp::Insert {
+            row: 0,
+            col: 1,
+            text: vec!["b".to_string()],
+        };
+        assert!(op.coalesce(&next));
+        match op {
+            EditOp::Insert { col, text, .. } => {
+                assert_eq!(col, 0);
+                assert_eq!(text, vec!["a".to_string(), "b".to_string()]);
+            }
+            _ => panic!("expected Insert"),
+        }
+    }
+
+    #[test]
+    fn coalesce_merges_repeated_backspace_to_the_left() {
+        // Backspace at col 2 removes "b" (leaving the caret at col 1), then
+        // backspace again removes "a" just to its left: the run should grow
+        // leftward with "a" ending up before "b".
+        let mut op = EditOp::Delete {
+            row: 0,
+            col: 1,
+            text: vec!["b".to_string()],
+        };
+        let next = EditOp::Delete {
+            row: 0,
+            col: 0,
+            text: vec!["a".to_string()],
+        };
+        assert!(op.coalesce(&next));
+        match op {
+            EditOp::Delete { col, text, .. } => {
+                assert_eq!(col, 0);
+                assert_eq!(text, vec!["a".to_string(), "b".to_string()]);
+            }
+            _ => panic!("expected Delete"),
+        }
+    }
+
+    #[test]
+    fn coalesce_refuses_to_merge_non_adjacent_edits() {
+        let mut op = EditOp::Insert {
+            row: 0,
+            col: 0,
+            text: vec!["a".to_string()],
+        };
+        let next = EditOp::Insert {
+            row: 0,
+            col: 5,
+            text: vec!["b".to_string()],
+        };
+        assert!(!op.coalesce(&next));
+    }
+
+    #[test]
+    fn record_coalesces_a_typed_run_into_a_single_undo_step() {
+        let mut editor = editor_with("");
+        editor.record(EditOp::Insert {
+            row: 0,
+            col: 0,
+            text: vec!["a".to_string()],
+        });
+        editor.record(EditOp::Insert {
+            row: 0,
+            col: 1,
+            text: vec!["b".to_string()],
+        });
+        // Both inserts land back to back, so they merge into one undo step
+        // instead of forcing a second undo to remove the whole word.
+        assert_eq!(editor.undo_stack.len(), 1);
+        match &editor.undo_stack[0] {
+            EditOp::Insert { col, text, .. } => {
+                assert_eq!(*col, 0);
+                assert_eq!(*text, vec!["a".to_string(), "b".to_string()]);
+            }
+            _ => panic!("expected Insert"),
+        }
+
+        // A fresh edit that doesn't abut the run starts a new undo step and
+        // clears any redo history.
+        editor.redo_stack.push(EditOp::Delete {
+            row: 0,
+            col: 0,
+            text: vec!["x".to_string()],
+        });
+        editor.record(EditOp::Insert {
+            row: 0,
+            col: 5,
+            text: vec!["c".to_string()],
+        });
+        assert_eq!(editor.undo_stack.len(), 2);
+        assert!(editor.redo_stack.is_empty());
+    }
+
+    // Ropey packs contiguous text into leaf chunks well under these sizes;
+    // inserting/deleting right at them exercises chunk splits and merges
+    // rather than staying inside a single leaf the whole time.
+    const BOUNDARY_OFFSETS: [usize; 4] = [511, 512, 1024, 4096];
+
+    #[test]
+    fn insert_and_delete_across_chunk_boundaries() {
+        let text = "a".repeat(8192);
+        for &offset in &BOUNDARY_OFFSETS {
+            let mut buffer = buffer_with(&text);
+
+            buffer.apply_op(&EditOp::Insert {
+                row: 0,
+                col: offset,
+                text: vec!["X".to_string()],
+            });
+            assert_eq!(buffer.rope.len_chars(), text.len() + 1);
+            assert_eq!(buffer.line_clusters(0)[offset], "X");
+
+            buffer.apply_op(&EditOp::Delete {
+                row: 0,
+                col: offset,
+                text: vec!["X".to_string()],
+            });
+            assert_eq!(buffer.rope.len_chars(), text.len());
+            assert_eq!(buffer.line_clusters(0), to_vec(&text));
+        }
+    }
+
+    #[test]
+    fn load_and_save_round_trip_a_large_file() {
+        let line = "the quick brown fox jumps over the lazy dog\n";
+        let lines_needed = 5 * 1024 * 1024 / line.len() + 1;
+
+        let mut original = tempfile::NamedTempFile::new().unwrap();
+        for _ in 0..lines_needed {
+            original.write_all(line.as_bytes()).unwrap();
+        }
+        original.flush().unwrap();
+
+        let mut buffer = FileBuffer::new();
+        buffer.load(original.path().to_path_buf()).unwrap();
+        assert_eq!(buffer.num_lines(), lines_needed);
+        assert!(!buffer.modified);
+
+        let mid = lines_needed / 2;
+        let caret = buffer.apply_op(&EditOp::Insert {
+            row: mid,
+            col: 0,
+            text: vec!["!".to_string()],
+        });
+        assert_eq!(caret, (mid, 1));
+        assert!(buffer.modified);
+
+        let saved = tempfile::NamedTempFile::new().unwrap();
+        buffer.save(saved.path().to_path_buf()).unwrap();
+
+        let mut reloaded = FileBuffer::new();
+        reloaded.load(saved.path().to_path_buf()).unwrap();
+        assert_eq!(reloaded.num_lines(), lines_needed);
+        assert_eq!(reloaded.line_clusters(mid)[0], "!");
+    }
+}